@@ -1,101 +1,434 @@
 use crate::{db, gpt};
 
-use chrono::NaiveDateTime;
+use chrono::{Days, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use sqlx::sqlite::SqlitePool;
-use std::{sync::Arc, time::Duration};
-use tokio::time::interval;
-use tracing::{error, info};
+use std::{collections::BTreeMap, collections::HashMap, sync::Arc, time::Duration};
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Discord caps an embed description at 4096 characters.
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+/// Discord allows at most 10 embeds per webhook message.
+const EMBEDS_PER_MESSAGE: usize = 10;
+
+/// How the recap loop decides when to fire the next cycle.
+pub enum RecapSchedule {
+    /// Fire every `Duration` from process start (drifts relative to wall-clock).
+    Interval(Duration),
+    /// Fire daily at a fixed local time-of-day in a named IANA timezone.
+    DailyAt { hour: u32, minute: u32, tz: Tz },
+}
+
+impl RecapSchedule {
+    /// Compute how long to sleep before the next cycle should run.
+    ///
+    /// For `Interval` this is simply the configured period. For `DailyAt` we
+    /// take "now" in the target timezone, build today's target instant, roll it
+    /// forward a day if it has already passed, and return the gap to `Utc::now()`
+    /// — letting `chrono-tz` resolve the correct offset across DST transitions.
+    fn next_delay(&self) -> Duration {
+        match self {
+            RecapSchedule::Interval(d) => *d,
+            RecapSchedule::DailyAt { hour, minute, tz } => {
+                let now = Utc::now().with_timezone(tz);
+                let today = now.date_naive().and_hms_opt(*hour, *minute, 0).unwrap();
+                let target_naive = if now.naive_local() >= today {
+                    today + Days::new(1)
+                } else {
+                    today
+                };
+                // Resolve the local wall-clock time back to a concrete instant,
+                // honoring the timezone's offset (and DST) for that date.
+                let target = tz
+                    .from_local_datetime(&target_naive)
+                    .earliest()
+                    .or_else(|| {
+                        // Skipped by a spring-forward gap: use the hour after it.
+                        tz.from_local_datetime(&(target_naive + chrono::Duration::hours(1)))
+                            .earliest()
+                    })
+                    .unwrap_or_else(|| tz.from_utc_datetime(&target_naive))
+                    .with_timezone(&Utc);
+                (target - Utc::now()).to_std().unwrap_or(Duration::ZERO)
+            }
+        }
+    }
+}
 
 pub struct DailyRecapService {
     db: Arc<SqlitePool>,
-    interval: Duration,
+    schedule: RecapSchedule,
+    /// Per-channel webhook overrides; channels absent here use `DISCORD_WEBHOOK`.
+    channel_webhooks: HashMap<i64, String>,
 }
 
 impl DailyRecapService {
     pub fn new(db: Arc<SqlitePool>, interval_seconds: u64) -> Self {
         Self {
             db,
-            interval: Duration::from_secs(interval_seconds),
+            schedule: RecapSchedule::Interval(Duration::from_secs(interval_seconds)),
+            channel_webhooks: HashMap::new(),
         }
     }
 
-    pub async fn run(&mut self) {
-        let mut interval_timer = interval(self.interval);
+    /// Build a service that fires daily at `hour:minute` local time in `tz`.
+    pub fn daily_at(db: Arc<SqlitePool>, hour: u32, minute: u32, tz: Tz) -> Self {
+        Self {
+            db,
+            schedule: RecapSchedule::DailyAt { hour, minute, tz },
+            channel_webhooks: HashMap::new(),
+        }
+    }
 
+    /// Set the channel-ID → webhook-URL mapping used to route per-channel
+    /// digests. Channels without an entry fall back to `DISCORD_WEBHOOK`.
+    pub fn with_channel_webhooks(mut self, channel_webhooks: HashMap<i64, String>) -> Self {
+        self.channel_webhooks = channel_webhooks;
+        self
+    }
+
+    pub async fn run(&mut self) {
         loop {
-            interval_timer.tick().await;
-            // Perform your task here
-            info!("Running daily recap of summaries...");
-
-            // Here, we should decide whether to fetch all summaries or only those after the last recap.
-            let last_recap: Option<(i32, NaiveDateTime)> =
-                sqlx::query_as::<_, (i32, NaiveDateTime)>(
-                    "SELECT id, timestamp FROM daily_digests ORDER BY timestamp DESC LIMIT 1",
-                )
-                .fetch_optional(&*self.db)
+            sleep(self.schedule.next_delay()).await;
+            // A single bad cycle (transient SQLite lock, DB gone) should log and
+            // be retried next tick rather than tear down the whole task.
+            if let Err(e) = self.run_cycle().await {
+                error!("Daily recap cycle failed, skipping: {e}");
+            }
+        }
+    }
+
+    /// Run one recap cycle: retry any previously-undelivered digests, then
+    /// generate and deliver a fresh digest per channel.
+    ///
+    /// Errors from the cycle's DB reads propagate so `run` can log and skip the
+    /// cycle; per-channel GPT, digest-insert and webhook failures are logged and
+    /// the remaining channels still proceed.
+    async fn run_cycle(&mut self) -> Result<(), sqlx::Error> {
+        info!("Running daily recap of summaries...");
+
+        let global_webhook = std::env::var("DISCORD_WEBHOOK").ok();
+
+        // Re-deliver digests that were generated on a previous tick but never
+        // successfully posted (e.g. Discord was rate-limiting or down).
+        for pending in db::fetch_undelivered_digests(&self.db).await? {
+            if self
+                .deliver(pending.id, pending.channel_id, &pending.text, &[], &global_webhook)
                 .await
-                .unwrap(); // Handle this error properly in production code
-
-            let summaries = match last_recap {
-                Some((_, last_timestamp)) => sqlx::query_as!(
-                    db::Summary,
-                    "SELECT * FROM summaries WHERE timestamp >= ? ORDER BY timestamp ASC",
-                    last_timestamp,
-                )
+            {
+                info!("Re-delivered pending digest {} for channel {}", pending.id, pending.channel_id);
+            }
+        }
+
+        // Here, we should decide whether to fetch all summaries or only those after the last recap.
+        let last_recap: Option<(i32, NaiveDateTime)> = sqlx::query_as::<_, (i32, NaiveDateTime)>(
+            "SELECT id, timestamp FROM daily_digests ORDER BY timestamp DESC LIMIT 1",
+        )
+        .fetch_optional(&*self.db)
+        .await?;
+
+        let summaries = match last_recap {
+            Some((_, last_timestamp)) => sqlx::query_as!(
+                db::Summary,
+                "SELECT * FROM summaries WHERE timestamp >= ? ORDER BY timestamp ASC",
+                last_timestamp,
+            )
+            .fetch_all(&*self.db)
+            .await?,
+            None => sqlx::query_as!(db::Summary, "SELECT * FROM summaries")
                 .fetch_all(&*self.db)
-                .await
-                .unwrap(),
-                None => sqlx::query_as!(db::Summary, "SELECT * FROM summaries")
-                    .fetch_all(&*self.db)
-                    .await
-                    .unwrap(),
-            };
+                .await?,
+        };
 
-            if summaries.is_empty() {
-                info!("No summaries to recap");
-                continue;
-            }
-            let summary_ids: Vec<i64> = summaries.iter().map(|s| s.id).collect();
+        if summaries.is_empty() {
+            info!("No summaries to recap");
+            return Ok(());
+        }
+        // Group the window's summaries by originating channel so each
+        // channel gets its own focused recap instead of one merged blob.
+        let mut by_channel: BTreeMap<i64, Vec<db::Summary>> = BTreeMap::new();
+        for summary in summaries {
+            by_channel.entry(summary.channel_id).or_default().push(summary);
+        }
 
-            let summaries_content: Vec<String> = summaries.into_iter().map(|s| s.text).collect();
+        for (channel_id, channel_summaries) in by_channel {
+            let summary_ids: Vec<i64> = channel_summaries.iter().map(|s| s.id).collect();
+            let summaries_content: Vec<String> =
+                channel_summaries.into_iter().map(|s| s.text).collect();
             let summaries_content = summaries_content.join(" ");
             let digest = match gpt::summarize(&summaries_content).await {
                 Ok(txt) => txt,
                 Err(e) => {
-                    error!("Could not summarize daily digest: {e}");
+                    error!("Could not summarize daily digest for channel {channel_id}: {e}");
                     continue;
                 }
             };
-            info!("Obtained a summarized daily digest: {digest}");
-            if let Err(e) = db::insert_daily_digest(&self.db, digest.clone(), summary_ids).await {
-                error!("Could not insert summarized daily digest into DB: {e}");
-                continue;
+            info!("Obtained a summarized daily digest for channel {channel_id}: {digest}");
+            // Persist first (undelivered), then attempt delivery and flip the
+            // flag on success — so a post that never lands is retried next tick.
+            let digest_id = match db::insert_daily_digest(
+                &self.db,
+                channel_id,
+                digest.clone(),
+                summary_ids.clone(),
+            )
+            .await
+            {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("Could not insert summarized daily digest into DB: {e}");
+                    continue;
+                }
+            };
+            info!("Saved daily digest for channel {channel_id} to DB");
+
+            self.deliver(digest_id, channel_id, &digest, &summary_ids, &global_webhook)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Post one digest to its channel webhook and, on success, mark it delivered.
+    ///
+    /// Returns whether the digest was delivered; a `false` leaves the row's
+    /// `delivered` flag unset so the next tick retries it. Placeholder tokens
+    /// are rendered here, immediately before the POST.
+    async fn deliver(
+        &self,
+        digest_id: i64,
+        channel_id: i64,
+        digest: &str,
+        summary_ids: &[i64],
+        global_webhook: &Option<String>,
+    ) -> bool {
+        // Route to the channel's own webhook, falling back to the global one.
+        let webhook_url = self
+            .channel_webhooks
+            .get(&channel_id)
+            .cloned()
+            .or_else(|| global_webhook.clone());
+        let Some(url) = webhook_url else {
+            error!("No webhook configured for channel {channel_id} and DISCORD_WEBHOOK is unset");
+            return false;
+        };
+
+        // Render any timestamp placeholders into live Discord markup before
+        // posting, so relative times stay accurate for every reader.
+        let rendered = substitute(digest);
+        if let Err(e) = self.push_digest(&url, &rendered, summary_ids).await {
+            error!("Error sending digest for channel {channel_id} to webhook: {e}");
+            return false;
+        }
+        info!("Successfully sent digest for channel {channel_id} to webhook");
+        if let Err(e) = db::mark_digest_delivered(&self.db, digest_id).await {
+            error!("Posted digest {digest_id} but could not mark it delivered: {e}");
+        }
+        true
+    }
+
+    /// Deliver `digest` to a Discord webhook as one or more rich embeds.
+    ///
+    /// The body is chunked onto `<=4096`-char embed descriptions and spread
+    /// across as many webhook POSTs as needed (Discord allows ten embeds per
+    /// message), so recaps longer than the 2000-char `content` limit are still
+    /// delivered in full. The first embed of every message carries the title,
+    /// and a footer records the generation time and the summary count / ID range
+    /// rolled up into the digest.
+    async fn push_digest(
+        &self,
+        webhook_url: &str,
+        digest: &str,
+        summary_ids: &[i64],
+    ) -> Result<(), reqwest::Error> {
+        let client = reqwest::Client::new();
+
+        let footer = {
+            let now = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+            match (summary_ids.first(), summary_ids.last()) {
+                (Some(first), Some(last)) => format!(
+                    "Generated {} • {} summaries (#{}–#{})",
+                    now,
+                    summary_ids.len(),
+                    first,
+                    last
+                ),
+                _ => format!("Generated {}", now),
             }
-            info!("Saved daily digest to DB");
-
-            // Push the new daily digest to the Discord webhook
-            if let Ok(webhook_url) = std::env::var("DISCORD_WEBHOOK") {
-                let client = reqwest::Client::new();
-                let payload = serde_json::json!({
-                    "content": format!("Daily Digest: {}", digest)
-                });
-
-                match client.post(&webhook_url).json(&payload).send().await {
-                    Ok(response) => {
-                        if response.status().is_success() {
-                            info!("Successfully sent daily digest to Discord webhook");
-                        } else {
-                            error!(
-                                "Failed to send daily digest to Discord webhook. Status: {}",
-                                response.status()
-                            );
-                        }
+        };
+
+        let chunks = chunk_digest(digest);
+        for page in chunks.chunks(EMBEDS_PER_MESSAGE) {
+            let embeds: Vec<_> = page
+                .iter()
+                .enumerate()
+                .map(|(i, block)| {
+                    let mut embed = serde_json::json!({ "description": block });
+                    if i == 0 {
+                        embed["title"] = serde_json::json!("Daily Digest");
                     }
-                    Err(e) => error!("Error sending daily digest to Discord webhook: {}", e),
-                }
+                    embed["footer"] = serde_json::json!({ "text": footer });
+                    embed
+                })
+                .collect();
+
+            let payload = serde_json::json!({ "embeds": embeds });
+            post_with_retry(&client, webhook_url, &payload).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Maximum number of retries for a single webhook POST on 5xx responses.
+const MAX_WEBHOOK_RETRIES: u32 = 5;
+/// Base backoff for 5xx retries; doubled each attempt up to `BACKOFF_CAP`.
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+/// Upper bound on a single 5xx backoff sleep.
+const BACKOFF_CAP: Duration = Duration::from_secs(30);
+
+/// POST `payload` to a webhook, honoring Discord's rate limiting.
+///
+/// On HTTP 429 the `Retry-After` header (or the JSON body's `retry_after`) is
+/// respected and the request is retried without consuming a backoff attempt; on
+/// 5xx responses we retry with capped exponential backoff. Other failures and a
+/// final exhausted retry surface as the underlying `reqwest::Error`.
+async fn post_with_retry(
+    client: &reqwest::Client,
+    webhook_url: &str,
+    payload: &serde_json::Value,
+) -> Result<(), reqwest::Error> {
+    let mut attempt: u32 = 0;
+    loop {
+        let response = client.post(webhook_url).json(payload).send().await?;
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(());
+        }
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let wait = retry_after(response).await;
+            warn!("Webhook rate limited (429); retrying after {:?}", wait);
+            sleep(wait).await;
+            continue;
+        }
+
+        if status.is_server_error() && attempt < MAX_WEBHOOK_RETRIES {
+            let backoff = (BACKOFF_BASE * 2u32.pow(attempt)).min(BACKOFF_CAP);
+            warn!("Webhook returned {}; retrying in {:?}", status, backoff);
+            sleep(backoff).await;
+            attempt += 1;
+            continue;
+        }
+
+        // Non-retryable status, or retries exhausted: surface the error.
+        response.error_for_status()?;
+        return Ok(());
+    }
+}
+
+/// Determine how long to wait after a 429, preferring the `Retry-After` header
+/// and falling back to the JSON body's `retry_after` (seconds, per Discord).
+async fn retry_after(response: reqwest::Response) -> Duration {
+    if let Some(secs) = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok())
+    {
+        return Duration::from_secs_f64(secs);
+    }
+    response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body.get("retry_after").and_then(|v| v.as_f64()))
+        .map(Duration::from_secs_f64)
+        .unwrap_or(BACKOFF_BASE)
+}
+
+/// Rewrite timestamp placeholders in a digest into Discord's native timestamp
+/// markup, following the convention reminder bots use.
+///
+/// Two tokens are recognised: `<<timenow>>` becomes `<t:EPOCH:F>` (long
+/// date/time at the moment of posting), and `<<timefrom:UNIX>>` becomes
+/// `<t:UNIX:R>` (a relative time such as "in 3 hours"). Everything else is
+/// left untouched.
+fn substitute(text: &str) -> String {
+    let now = Utc::now().timestamp();
+    let out = regex::Regex::new(r"<<timenow>>")
+        .unwrap()
+        .replace_all(text, format!("<t:{now}:F>").as_str())
+        .into_owned();
+    regex::Regex::new(r"<<timefrom:(?P<time>\d+)>>")
+        .unwrap()
+        .replace_all(&out, "<t:$time:R>")
+        .into_owned()
+}
+
+/// Integer division returning both quotient and remainder.
+fn div_rem(value: i64, divisor: i64) -> (i64, i64) {
+    (value / divisor, value % divisor)
+}
+
+/// Expand a duration `format` string into a plain-text elapsed span, where
+/// `%d`/`%h`/`%m`/`%s` are replaced with the whole days/hours/minutes/seconds
+/// that `seconds` decomposes into. Lets prompt templates request spans like
+/// "since the last recap" without Discord markup.
+pub fn fmt_displacement(format: &str, seconds: i64) -> String {
+    let (days, rem) = div_rem(seconds, 86_400);
+    let (hours, rem) = div_rem(rem, 3_600);
+    let (minutes, secs) = div_rem(rem, 60);
+    format
+        .replace("%d", &days.to_string())
+        .replace("%h", &hours.to_string())
+        .replace("%m", &minutes.to_string())
+        .replace("%s", &secs.to_string())
+}
+
+/// Split a digest into `<=4096`-char blocks, preferring paragraph and then
+/// sentence boundaries so embeds don't tear words or thoughts in half.
+fn chunk_digest(digest: &str) -> Vec<String> {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    let mut push_unit = |unit: &str, current: &mut String, blocks: &mut Vec<String>| {
+        if current.chars().count() + unit.chars().count() > EMBED_DESCRIPTION_LIMIT
+            && !current.is_empty()
+        {
+            blocks.push(std::mem::take(current));
+        }
+        current.push_str(unit);
+    };
+
+    for paragraph in digest.split_inclusive("\n\n") {
+        if paragraph.chars().count() <= EMBED_DESCRIPTION_LIMIT {
+            push_unit(paragraph, &mut current, &mut blocks);
+            continue;
+        }
+        // Oversized paragraph: fall back to sentence boundaries.
+        for sentence in paragraph.split_inclusive(". ") {
+            if sentence.chars().count() <= EMBED_DESCRIPTION_LIMIT {
+                push_unit(sentence, &mut current, &mut blocks);
             } else {
-                error!("DISCORD_WEBHOOK environment variable not set");
+                // Pathological run with no boundaries: hard-split on char count.
+                for ch in sentence.chars() {
+                    if current.chars().count() + 1 > EMBED_DESCRIPTION_LIMIT {
+                        blocks.push(std::mem::take(&mut current));
+                    }
+                    current.push(ch);
+                }
             }
         }
     }
+
+    if !current.is_empty() {
+        blocks.push(current);
+    }
+    if blocks.is_empty() {
+        blocks.push(String::new());
+    }
+    blocks
 }
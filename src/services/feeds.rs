@@ -0,0 +1,114 @@
+use crate::db;
+
+use chrono::{DateTime, NaiveDateTime};
+use sqlx::sqlite::SqlitePool;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::time::interval;
+use tracing::{error, info};
+
+/// A post pulled from a Mastodon-style public timeline or a generic JSON feed.
+///
+/// `content` arrives as HTML and is stripped to plain text before storage,
+/// mirroring the shape the caveman spider uses for scraped items.
+#[derive(serde::Deserialize)]
+pub struct Post {
+    pub created_at: String,
+    pub url: String,
+    pub content: String,
+    pub account: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Periodically pulls external feeds and folds their posts into the `summaries`
+/// table so `DailyRecapService` rolls them into the daily digest alongside
+/// Discord chatter, turning the tool into a cross-platform digest.
+pub struct FeedIngestService {
+    db: Arc<SqlitePool>,
+    interval: Duration,
+    feeds: Vec<String>,
+    client: reqwest::Client,
+    seen: HashSet<String>,
+}
+
+impl FeedIngestService {
+    pub fn new(db: Arc<SqlitePool>, interval_seconds: u64, feeds: Vec<String>) -> Self {
+        Self {
+            db,
+            interval: Duration::from_secs(interval_seconds),
+            feeds,
+            client: reqwest::Client::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    pub async fn run(&mut self) {
+        let mut interval_timer = interval(self.interval);
+
+        loop {
+            interval_timer.tick().await;
+            info!("Polling {} external feed(s)...", self.feeds.len());
+
+            for feed_url in self.feeds.clone() {
+                if let Err(e) = self.ingest_feed(&feed_url).await {
+                    error!("Could not ingest feed {feed_url}: {e}");
+                }
+            }
+        }
+    }
+
+    /// Fetch one feed, decode its posts, and insert each unseen item into the
+    /// summary pool with its source URL retained.
+    async fn ingest_feed(&mut self, feed_url: &str) -> Result<(), reqwest::Error> {
+        let posts: Vec<Post> = self.client.get(feed_url).send().await?.json().await?;
+
+        for post in posts {
+            if !self.seen.insert(post.url.clone()) {
+                continue;
+            }
+
+            let text = strip_html(&post.content);
+            let created_at = match parse_created_at(&post.created_at) {
+                Some(ts) => ts,
+                None => {
+                    error!("Skipping feed item with unparseable created_at: {}", post.created_at);
+                    self.seen.remove(&post.url);
+                    continue;
+                }
+            };
+
+            if let Err(e) =
+                db::insert_summary(&self.db, text, created_at, post.account, post.url.clone()).await
+            {
+                error!("Could not insert feed item {} into DB: {e}", post.url);
+                // Keep it unseen so the next tick retries the insert.
+                self.seen.remove(&post.url);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse an RFC3339 feed timestamp into a naive UTC datetime for storage.
+fn parse_created_at(created_at: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(created_at)
+        .ok()
+        .map(|dt| dt.naive_utc())
+}
+
+/// Strip HTML tags from feed content, leaving plain text. Mastodon wraps post
+/// bodies in `<p>`/`<br>` markup that would otherwise leak into the digest.
+fn strip_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(ch),
+            _ => {}
+        }
+    }
+    out.trim().to_string()
+}